@@ -1,12 +1,30 @@
 //! The lexer is responsible for converting user input to a
 //! well defined context-free grammar.
 
+use std::error::Error;
+use std::fmt;
+
+/// An error encountered while lexing, along with the character offset (from
+/// the start of the input) at which it occurred.
+#[derive(Debug)]
+pub struct LexError {
+    pub message: String,
+    pub pos: usize
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.pos)
+    }
+}
+
+impl Error for LexError {}
+
 #[derive(PartialEq)]
 #[derive(Debug)]
 
  /// This defines the "types" of numbers that are recognized. Currently,
-/// only integers and decimals are distinguished. In the future, this could
-/// be extended to include different bases like hex or binary numbers.
+/// only integers and decimals are distinguished.
 ///
 /// **NOTE** It's not immediately clear that we even need to distinguish
 /// integers from decimals since one is a subset of the other. Once I have
@@ -14,7 +32,9 @@
 /// superfluous this could be removed.
 pub enum Num {
     /// An integer. Due to how the "negation" operation is handled, these will
-    /// actually always be lexed as positive numbers.
+    /// actually always be lexed as positive numbers. Hex (`0x`), binary
+    /// (`0b`), and octal (`0o`) literals are folded into this variant too,
+    /// already converted to their decimal value.
     Integer(i64),
 
     /// A decimal number. Consists of an integral part, decimal part, and
@@ -80,12 +100,76 @@ fn is_grouping(c: char) -> bool {
     }
 }
 
+/// Calculate the value of a digit in the given base (2, 8, 10, or 16), if
+/// applicable. Hex digits may be upper or lower case.
+fn to_base_digit(c: char, base: u32) -> Option<u8> {
+    let d = match c {
+        '0'...'9' => c as u8 - b'0',
+        'a'...'f' => c as u8 - b'a' + 10,
+        'A'...'F' => c as u8 - b'A' + 10,
+        _ => return None
+    };
+
+    if (d as u32) < base {
+        Some(d)
+    } else {
+        None
+    }
+}
+
+/// Attempt to consume a `0x`/`0b`/`0o` base-prefixed integer literal from the
+/// front of the character list. Returns `Ok(None)` if there's no such prefix
+/// (leaving `chars` untouched), `Ok(Some(n))` on success, or `Err` if the
+/// prefix isn't followed by any valid digits in that base or the value
+/// doesn't fit in an `i64`.
+fn lex_based_num(chars: &mut Vec<char>) -> Result<Option<Num>, String> {
+    if chars.last() != Some(&'0') {
+        return Ok(None);
+    }
+
+    let zero = chars.pop().unwrap();
+    let base = match chars.last() {
+        Some(&'x') => 16,
+        Some(&'b') => 2,
+        Some(&'o') => 8,
+        _ => { chars.push(zero); return Ok(None); }
+    };
+    let marker = chars.pop().unwrap();
+
+    let mut whole_num: i64 = 0;
+    let mut parsed_any = false;
+
+    while let Some(c) = chars.pop() {
+        match to_base_digit(c, base) {
+            Some(d) => {
+                whole_num = whole_num.checked_mul(base as i64)
+                    .and_then(|n| n.checked_add(d as i64))
+                    .ok_or_else(|| "number is too large".to_string())?;
+                parsed_any = true;
+            },
+            None => {
+                chars.push(c);
+                break;
+            }
+        }
+    }
+
+    if !parsed_any {
+        chars.push(marker);
+        chars.push(zero);
+        return Err(format!("expected digits after '0{}'", marker));
+    }
+
+    Ok(Some(Num::Integer(whole_num)))
+}
+
 /// Given a vector of characters such that the left-most character is on top,
 /// attempt to extract a number from the front of the character list.
 /// If a valid number exists, parse it and return Some(n, cs) where n is the
 /// Num value parsed and cs is the remaining unlexed characters. Otherwise,
-/// return None.
-fn lex_num(chars: &mut Vec<char>) -> Option<Num> {
+/// return None. Errors if a `0x`/`0b`/`0o` prefix isn't followed by digits
+/// or the based literal doesn't fit in an `i64`.
+fn lex_num(chars: &mut Vec<char>) -> Result<Option<Num>, String> {
     let mut whole_num : i64 = 0;
     let mut decimal : u64 = 0;
     let mut exponent : i16 = 0;
@@ -93,14 +177,18 @@ fn lex_num(chars: &mut Vec<char>) -> Option<Num> {
     let mut parsed_zeroes = false;
 
     match chars.last() {
-        None => return None,
+        None => return Ok(None),
         Some(&c) => {
             if is_num(c) == false && c != '.' {
-                return None
+                return Ok(None)
             }
         }
     }
 
+    if let Some(n) = lex_based_num(chars)? {
+        return Ok(Some(n));
+    }
+
     while let Some(c) = chars.pop() {
         match (c, to_digit(c), is_dec, parsed_zeroes) {
             (_, Some(d), false, false) => {
@@ -127,9 +215,9 @@ fn lex_num(chars: &mut Vec<char>) -> Option<Num> {
     }
 
     if is_dec {
-        return Some(Num::Decimal(whole_num, decimal, exponent));
+        Ok(Some(Num::Decimal(whole_num, decimal, exponent)))
     } else {
-        return Some(Num::Integer(whole_num));
+        Ok(Some(Num::Integer(whole_num)))
     }
 }
 /// Given a vector of characters such that the left-most character is on top,
@@ -183,54 +271,69 @@ fn lex_oper(chars: &mut Vec<char>) -> Option<String> {
 
 /// Given a vector of characters such that the left-most character is on top,
 /// attempt to extract a sequence of Tokens according to the grammar defined
-/// above.
-fn _lex(chars: &mut Vec<char>) -> Vec<Token> {
+/// above. Each token is paired with the character offset (from the start of
+/// the input) at which it begins, so later stages can report where a
+/// problem occurred.
+fn _lex(chars: &mut Vec<char>) -> Result<Vec<(Token, usize)>, LexError> {
     let mut chars = chars;
-    let mut v : Vec<Token> = vec![];
+    let mut v : Vec<(Token, usize)> = vec![];
+    let mut pos: usize = 0;
 
     while let Some(c) = chars.pop() {
         match c {
-            '(' => v.push(Token::OParen),
-            ')' => v.push(Token::CParen),
-            '[' => v.push(Token::OBracket),
-            ']' => v.push(Token::CBracket),
-            ' ' => continue,
+            '(' => { v.push((Token::OParen, pos)); pos += 1; },
+            ')' => { v.push((Token::CParen, pos)); pos += 1; },
+            '[' => { v.push((Token::OBracket, pos)); pos += 1; },
+            ']' => { v.push((Token::CBracket, pos)); pos += 1; },
+            ' ' => { pos += 1; },
             _ => {
                 chars.push(c);
-
-                if let Some(n) = lex_num(&mut chars) {
-                    v.push(Token::Number(n));
-                    continue;
+                let start = pos;
+                let remaining = chars.len();
+
+                match lex_num(&mut chars) {
+                    Ok(Some(n)) => {
+                        pos += remaining - chars.len();
+                        v.push((Token::Number(n), start));
+                        continue;
+                    },
+                    Ok(None) => {},
+                    Err(message) => return Err(LexError { message: message, pos: start })
                 }
 
                 if let Some(s) = lex_word(&mut chars) {
-                    v.push(Token::Word(s));
+                    pos += remaining - chars.len();
+                    v.push((Token::Word(s), start));
                     continue;
                 }
 
                 if let Some(o) = lex_oper(&mut chars) {
-                    v.push(Token::Oper(o));
+                    pos += remaining - chars.len();
+                    v.push((Token::Oper(o), start));
                     continue;
                 }
 
-                panic!("Lex error!");
+                return Err(LexError {
+                    message: format!("unexpected character '{}'", c),
+                    pos: pos
+                });
             }
         }
     }
 
-    return v;
+    Ok(v)
 }
 
 /// Given a string representing a mathematical something or other, extract
-/// a sequence of tokens that represent the string according to the grammar
-/// defined above.
-pub fn lex(text: String) -> Vec<Token> {
+/// a sequence of (token, position) pairs that represent the string
+/// according to the grammar defined above.
+pub fn lex(text: String) -> Result<Vec<(Token, usize)>, LexError> {
     let mut chars: Vec<char> = text.chars().collect();
 
     // Rust pops and pushes from the back meaning the left-most char is on the
     // "bottom". Reverse chars so the left most char is on top
     chars.reverse();
-    return _lex(&mut chars);
+    _lex(&mut chars)
 }
 
 #[cfg(test)]
@@ -246,9 +349,15 @@ mod tests {
         return string;
     }
 
+    // Lex and throw away the position info, keeping the token stream tests
+    // focused on what they're actually asserting.
+    fn toks(s: &str) -> Vec<Token> {
+        lex(quiet_from_str(s)).unwrap().into_iter().map(|(t, _)| t).collect()
+    }
+
     #[test]
     fn grouping() {
-        let res = lex(quiet_from_str("[[()]()]"));
+        let res = toks("[[()]()]");
         let expected = vec![
             Token::OBracket,
             Token::OBracket,
@@ -265,17 +374,56 @@ mod tests {
 
     #[test]
     fn numbers() {
-        let res = lex(quiet_from_str("1337"));
+        let res = toks("1337");
         assert_eq!(vec![Token::Number(Num::Integer(1337))], res);
 
-        let res = lex(quiet_from_str("98"));
+        let res = toks("98");
         assert_eq!(vec![Token::Number(Num::Integer(98))], res);
 
-        let res = lex(quiet_from_str("3.1415"));
+        let res = toks("3.1415");
         assert_eq!(vec![Token::Number(Num::Decimal(3, 1415, 0))], res);
 
-        let res = lex(quiet_from_str(".001"));
+        let res = toks(".001");
         assert_eq!(vec![Token::Number(Num::Decimal(0, 1, -2))], res);
+
+        // A bare zero, or a zero followed by a decimal point, must not be
+        // mistaken for the start of a base prefix.
+        let res = toks("0");
+        assert_eq!(vec![Token::Number(Num::Integer(0))], res);
+
+        let res = toks("0.5");
+        assert_eq!(vec![Token::Number(Num::Decimal(0, 5, 0))], res);
+    }
+
+    #[test]
+    fn based_numbers() {
+        let res = toks("0x1F");
+        assert_eq!(vec![Token::Number(Num::Integer(31))], res);
+
+        let res = toks("0b1010");
+        assert_eq!(vec![Token::Number(Num::Integer(10))], res);
+
+        let res = toks("0o17");
+        assert_eq!(vec![Token::Number(Num::Integer(15))], res);
+
+        let res = toks("1 + 0x10");
+        assert_eq!(vec![Token::Number(Num::Integer(1)),
+                         Token::Oper(quiet_from_str("+")),
+                         Token::Number(Num::Integer(16))], res);
+    }
+
+    #[test]
+    fn based_number_without_digits_is_an_error() {
+        let err = lex(quiet_from_str("0x")).unwrap_err();
+        assert_eq!(err.pos, 0);
+
+        assert!(lex(quiet_from_str("0b")).is_err());
+        assert!(lex(quiet_from_str("0o")).is_err());
+    }
+
+    #[test]
+    fn based_number_that_overflows_is_an_error() {
+        assert!(lex(quiet_from_str("0xFFFFFFFFFFFFFFFFF")).is_err());
     }
 
     #[test]
@@ -284,25 +432,25 @@ mod tests {
         let sin = quiet_from_str("sin");
         let cos = quiet_from_str("cos");
 
-        let res = lex(quiet_from_str("tan"));
+        let res = toks("tan");
         assert_eq!(vec![Token::Word(tan)], res);
 
-        let res = lex(quiet_from_str("sin     cos   "));
+        let res = toks("sin     cos   ");
         assert_eq!(vec![Token::Word(sin), Token::Word(cos)], res);
     }
 
     #[test]
     fn ops() {
-        let res = lex(quiet_from_str("+"));
+        let res = toks("+");
         assert_eq!(vec![Token::Oper(quiet_from_str("+"))], res);
 
-        let res = lex(quiet_from_str(" >=   ++"));
+        let res = toks(" >=   ++");
         assert_eq!(vec![Token::Oper(quiet_from_str(">=")), Token::Oper(quiet_from_str("++"))], res);
     }
 
     #[test]
     fn expr() {
-        let res = lex(quiet_from_str("2* 3.1415 >= 5"));
+        let res = toks("2* 3.1415 >= 5");
 
         let sol = vec![Token::Number(Num::Integer(2)),
                        Token::Oper(quiet_from_str("*")),
@@ -311,7 +459,7 @@ mod tests {
                        Token::Number(Num::Integer(5))];
         assert_eq!(res, sol);
 
-        let res = lex(quiet_from_str("2/(pi - x^2) = 2.018"));
+        let res = toks("2/(pi - x^2) = 2.018");
         let sol = vec![Token::Number(Num::Integer(2)),
                        Token::Oper(quiet_from_str("/")),
                        Token::OParen,
@@ -325,4 +473,11 @@ mod tests {
                        Token::Number(Num::Decimal(2, 18, -1))];
         assert_eq!(res, sol);
     }
+
+    #[test]
+    fn positions() {
+        let res = lex(quiet_from_str("12 + 3")).unwrap();
+        let positions: Vec<usize> = res.into_iter().map(|(_, pos)| pos).collect();
+        assert_eq!(vec![0, 3, 5], positions);
+    }
 }
@@ -1,10 +1,13 @@
 pub mod lexer;
 pub mod rational;
 pub mod parser;
+pub mod vm;
 pub mod repl;
 
 fn main() {
+    let mut env = parser::Env::new ();
+
     loop {
-        repl::handle (repl::read ());
+        repl::handle (repl::read (), &mut env);
     }
 }
@@ -1,15 +1,196 @@
-use rational::Rational;
-use lexer::Token;
-use lexer::Num;
+use rational::{Rational, DivByZeroError, UnsupportedExponentError};
+use lexer::{Num, Token};
+use vm::{Instr, CompileError};
 
+use std::collections::HashMap;
+use std::error::Error;
 use std::fmt;
 
+/// An error encountered while converting a token stream into an
+/// `Expression`, along with the input position (if known) at which it
+/// occurred.
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub pos: Option<usize>
+}
+
+impl ParseError {
+    fn new(message: &str, pos: Option<usize>) -> ParseError {
+        ParseError { message: message.to_string(), pos: pos }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pos {
+            Some(pos) => write!(f, "{} at column {}", self.message, pos),
+            None => write!(f, "{}", self.message)
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// An error encountered while evaluating an `Expression`, e.g. division by
+/// zero or an unsupported function call.
+#[derive(Debug)]
+pub struct EvalError {
+    pub message: String
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for EvalError {}
+
+impl From<DivByZeroError> for EvalError {
+    fn from(e: DivByZeroError) -> EvalError {
+        EvalError { message: e.to_string() }
+    }
+}
+
+impl From<UnsupportedExponentError> for EvalError {
+    fn from(e: UnsupportedExponentError) -> EvalError {
+        EvalError { message: e.to_string() }
+    }
+}
+
 pub trait UnaryOperator : fmt::Debug {
     fn apply(&self, expr: Box<Expression>) -> Box<Expression>;
+    fn eval(&self, val: Rational) -> Result<Rational, EvalError>;
+    /// Emit this operator's bytecode instruction, if the VM supports it.
+    fn compile(&self, instrs: &mut Vec<Instr>) -> Result<(), CompileError>;
 }
 
 pub trait BinaryOperator : fmt::Debug {
     fn apply(&self, expr1: Box<Expression>, expr2: Box<Expression>) -> Box<Expression>;
+    fn eval(&self, left: Rational, right: Rational) -> Result<Rational, EvalError>;
+    /// Emit this operator's bytecode instruction, if the VM supports it.
+    fn compile(&self, instrs: &mut Vec<Instr>) -> Result<(), CompileError>;
+}
+
+#[derive(Debug)]
+pub struct Add;
+#[derive(Debug)]
+pub struct Sub;
+#[derive(Debug)]
+pub struct Mul;
+#[derive(Debug)]
+pub struct Div;
+
+impl BinaryOperator for Add {
+    fn apply(&self, expr1: Box<Expression>, expr2: Box<Expression>) -> Box<Expression> {
+        Box::new(Expression::BinaryOp(Box::new(Add), expr1, expr2))
+    }
+
+    fn eval(&self, left: Rational, right: Rational) -> Result<Rational, EvalError> {
+        Ok(left + right)
+    }
+
+    fn compile(&self, instrs: &mut Vec<Instr>) -> Result<(), CompileError> {
+        instrs.push(Instr::Add);
+        Ok(())
+    }
+}
+
+impl BinaryOperator for Sub {
+    fn apply(&self, expr1: Box<Expression>, expr2: Box<Expression>) -> Box<Expression> {
+        Box::new(Expression::BinaryOp(Box::new(Sub), expr1, expr2))
+    }
+
+    fn eval(&self, left: Rational, right: Rational) -> Result<Rational, EvalError> {
+        Ok(left - right)
+    }
+
+    fn compile(&self, instrs: &mut Vec<Instr>) -> Result<(), CompileError> {
+        instrs.push(Instr::Sub);
+        Ok(())
+    }
+}
+
+impl BinaryOperator for Mul {
+    fn apply(&self, expr1: Box<Expression>, expr2: Box<Expression>) -> Box<Expression> {
+        Box::new(Expression::BinaryOp(Box::new(Mul), expr1, expr2))
+    }
+
+    fn eval(&self, left: Rational, right: Rational) -> Result<Rational, EvalError> {
+        Ok(left * right)
+    }
+
+    fn compile(&self, instrs: &mut Vec<Instr>) -> Result<(), CompileError> {
+        instrs.push(Instr::Mul);
+        Ok(())
+    }
+}
+
+impl BinaryOperator for Div {
+    fn apply(&self, expr1: Box<Expression>, expr2: Box<Expression>) -> Box<Expression> {
+        Box::new(Expression::BinaryOp(Box::new(Div), expr1, expr2))
+    }
+
+    fn eval(&self, left: Rational, right: Rational) -> Result<Rational, EvalError> {
+        Ok((left / right)?)
+    }
+
+    fn compile(&self, instrs: &mut Vec<Instr>) -> Result<(), CompileError> {
+        instrs.push(Instr::Div);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Pow;
+
+impl BinaryOperator for Pow {
+    fn apply(&self, expr1: Box<Expression>, expr2: Box<Expression>) -> Box<Expression> {
+        Box::new(Expression::BinaryOp(Box::new(Pow), expr1, expr2))
+    }
+
+    fn eval(&self, left: Rational, right: Rational) -> Result<Rational, EvalError> {
+        Ok(left.pow(&right)?)
+    }
+
+    fn compile(&self, _instrs: &mut Vec<Instr>) -> Result<(), CompileError> {
+        Err(CompileError { message: "exponentiation is not yet supported by the bytecode compiler".to_string() })
+    }
+}
+
+#[derive(Debug)]
+pub struct Neg;
+#[derive(Debug)]
+pub struct Pos;
+
+impl UnaryOperator for Neg {
+    fn apply(&self, expr: Box<Expression>) -> Box<Expression> {
+        Box::new(Expression::UnaryOp(Box::new(Neg), expr))
+    }
+
+    fn eval(&self, val: Rational) -> Result<Rational, EvalError> {
+        Ok(-val)
+    }
+
+    fn compile(&self, instrs: &mut Vec<Instr>) -> Result<(), CompileError> {
+        instrs.push(Instr::Neg);
+        Ok(())
+    }
+}
+
+impl UnaryOperator for Pos {
+    fn apply(&self, expr: Box<Expression>) -> Box<Expression> {
+        Box::new(Expression::UnaryOp(Box::new(Pos), expr))
+    }
+
+    fn eval(&self, val: Rational) -> Result<Rational, EvalError> {
+        Ok(val)
+    }
+
+    fn compile(&self, _instrs: &mut Vec<Instr>) -> Result<(), CompileError> {
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -17,9 +198,39 @@ pub trait BinaryOperator : fmt::Debug {
 pub enum Expression {
     UnaryOp(Box<UnaryOperator>, Box<Expression>),
     BinaryOp(Box<BinaryOperator>, Box<Expression>, Box<Expression>),
+    /// A word used as an operand: the name and its argument expressions.
+    /// An empty argument list (a bare word, or a call with empty parens
+    /// such as `f()`) is a variable or constant reference; one argument is
+    /// a unary built-in function call.
+    Call(String, Vec<Box<Expression>>),
+    /// `name = expr`: evaluates `expr` and binds it to `name` in the
+    /// environment, recognized by `parse` as a special top-level form.
+    Assign(String, Box<Expression>),
     Value(Rational)
 }
 
+/// Look up the concrete `BinaryOperator` for an operator token's lexeme.
+fn build_binary_op(s: &str) -> Option<Box<BinaryOperator>> {
+    match s {
+        "+" => Some(Box::new(Add)),
+        "-" => Some(Box::new(Sub)),
+        "*" => Some(Box::new(Mul)),
+        "/" => Some(Box::new(Div)),
+        "^" => Some(Box::new(Pow)),
+        _ => None
+    }
+}
+
+/// Look up the concrete `UnaryOperator` for a (already-reclassified) prefix
+/// operator token's lexeme.
+fn build_unary_op(s: &str) -> Option<Box<UnaryOperator>> {
+    match s {
+        "u-" => Some(Box::new(Neg)),
+        "u+" => Some(Box::new(Pos)),
+        _ => None
+    }
+}
+
 struct OpInfo {
     left_assoc: bool,
     precedence: u8
@@ -30,6 +241,8 @@ fn get_op_info (op_token: &Token) -> Option<OpInfo> {
         return match s.as_ref() {
             "+" | "-" => Some (OpInfo {left_assoc: true, precedence: 0}),
             "*" | "/" => Some (OpInfo {left_assoc: true, precedence: 1}),
+            "u-" | "u+" => Some (OpInfo {left_assoc: false, precedence: 2}),
+            "^" => Some (OpInfo {left_assoc: false, precedence: 3}),
             _ => None
         }
     }
@@ -37,81 +250,159 @@ fn get_op_info (op_token: &Token) -> Option<OpInfo> {
     None
 }
 
-fn parse_num(tokens: &mut Vec<Token>) -> Option<Expression> {
-    return match tokens.pop() {
-        Some(Token::Number(n)) =>
-            Some(Expression::Value(Rational::from_num(n))),
-        Some(x) => {
-            tokens.push(x);
-            None
-        },
-        None => None
-    }
-}
-
-fn _parse(tokens: &mut Vec<Token>, expr_stack: &mut Vec<Expression>) {
-
-    if let Some(x) = parse_num(tokens) {
-        expr_stack.push(x);
-        _parse(tokens, expr_stack);
-    }
-
+/// Whether the token just seen could be followed by a prefix (unary)
+/// operator, as opposed to a binary one: true at the start of the input,
+/// right after another operator, or right after an opening paren/bracket.
+#[derive(PartialEq)]
+enum Context {
+    ExpectOperand,
+    ExpectOperator
 }
 
 // Use Shunting-Yard algorithm to convert infix expression into RPN (postfix)
-// notation
-fn to_postfix(tokens: &mut Vec<Token>, output: &mut Vec<Token>) {
+// notation. Each token carries its source position so a malformed-input
+// error can say where the problem is. `calls` maps the position of every
+// `Word` that turned out to be a function call (immediately followed by
+// `(`) to its arity, so `build_expression` can tell those apart from a bare
+// word used as a variable reference and knows how many operands to pop for
+// each; a token's position is unique within one input, so it doubles as an
+// identity for this purpose.
+fn to_postfix(tokens: &mut Vec<(Token, usize)>, output: &mut Vec<(Token, usize)>, calls: &mut HashMap<usize, usize>) -> Result<(), ParseError> {
     // Shunting yard reads tokens from LTR, but popping from a vec pops from
     // the back, so to pop the left-most token first, we need to reverse it.
     tokens.reverse();
 
-    let mut oper_stack: Vec<Token> = vec![];
-    while let Some(t) = tokens.pop() {
+    let mut oper_stack: Vec<(Token, usize)> = vec![];
+    let mut context = Context::ExpectOperand;
+
+    while let Some((t, pos)) = tokens.pop() {
+        let mut t = t;
+
+        // `lex_oper` greedily merges adjacent operator characters with no
+        // regard for whitespace, so a unary sign written right after a
+        // binary operator (`2^-1`, `3*-2`) comes through as one compound
+        // lexeme (`"^-"`, `"*-"`) instead of two tokens. Peel a single
+        // trailing sign off such a token and feed it back through as its
+        // own `Oper`, so the context check below can reclassify it as a
+        // unary operator like it would if the input had a space in it.
+        if let Token::Oper(ref s) = t {
+            let ends_in_sign = s.len() > 1 && (s.ends_with('-') || s.ends_with('+'));
+            if ends_in_sign {
+                let split_at = s.len() - 1;
+                let sign = s[split_at..].to_string();
+                let prefix = s[..split_at].to_string();
+                tokens.push((Token::Oper(sign), pos + split_at));
+                t = Token::Oper(prefix);
+            }
+        }
+
+        // A '-' or '+' is a unary (prefix) operator, not a binary one, when
+        // it can't possibly have a left operand: at the start of input,
+        // right after another operator, or right after an open grouping.
+        if context == Context::ExpectOperand {
+            if let Token::Oper(ref s) = t {
+                if s.as_str() == "-" || s.as_str() == "+" {
+                    t = Token::Oper(if s.as_str() == "-" { "u-".to_string() } else { "u+".to_string() });
+                }
+            }
+        }
+
+        context = match t {
+            Token::Number(_) | Token::Word(_) | Token::CParen | Token::CBracket =>
+                Context::ExpectOperator,
+            Token::OParen | Token::OBracket | Token::Oper(_) =>
+                Context::ExpectOperand,
+        };
+
         match t {
-            Token::Number(_) => output.insert(0, t),
-            Token::Word(ref s) => oper_stack.push(Token::Word(s.clone())),
-            Token::Comma => {
+            Token::Number(_) => output.insert(0, (t, pos)),
+            Token::Word(ref s) => {
+                // A word immediately followed by `(` is a function call and
+                // must wait on the operator stack for its closing paren; a
+                // bare word is a variable reference and, like a number,
+                // goes straight to the output. A call's arity starts at 0
+                // for an empty argument list (`fun()`) or 1 otherwise, and
+                // is bumped by one for each top-level comma seen before its
+                // closing paren.
+                let is_call = match tokens.last() {
+                    Some(&(Token::OParen, _)) => true,
+                    _ => false
+                };
+
+                if is_call {
+                    let is_empty_call = match tokens.get(tokens.len().wrapping_sub(2)) {
+                        Some(&(Token::CParen, _)) => true,
+                        _ => false
+                    };
+
+                    calls.insert(pos, if is_empty_call { 0 } else { 1 });
+                    oper_stack.push((Token::Word(s.clone()), pos));
+                } else {
+                    output.insert(0, (Token::Word(s.clone()), pos));
+                }
+            },
+            Token::Oper(ref s) if s.as_str() == "," => {
                 loop {
                     match oper_stack.pop() {
-                        Some(Token::OParen) => oper_stack.push(Token::OParen),
-                        Some(Token::OBracket) => oper_stack.push(Token::OBracket),
+                        Some((Token::OParen, ppos)) => {
+                            if let Some(&(Token::Word(_), wpos)) = oper_stack.last() {
+                                if let Some(arity) = calls.get_mut(&wpos) {
+                                    *arity += 1;
+                                }
+                            }
+                            oper_stack.push((Token::OParen, ppos));
+                            break;
+                        },
+                        Some((Token::OBracket, ppos)) => { oper_stack.push((Token::OBracket, ppos)); break; },
                         Some(o) => output.insert(0, o),
-                        None => panic!("Mismatched parens or brackets!")
+                        None => return Err(ParseError::new("comma outside of a function call", Some(pos)))
                     }
                 }
             },
+            // A prefix operator hasn't consumed a left operand yet, so it
+            // has nothing to resolve against what's already on the stack -
+            // it just waits there for its own (upcoming) operand, the same
+            // way an open paren does. Running it through the reduce loop
+            // below like an infix operator would pop whatever it's stacked
+            // on top of (e.g. `^` in `2^-1`) before that operator's real
+            // right operand has even been read.
+            Token::Oper(ref s) if s.as_str() == "u-" || s.as_str() == "u+" =>
+                oper_stack.push((Token::Oper(s.clone()), pos)),
             Token::Oper(ref s) => {
-                let info1 = get_op_info(&t).expect("Unkown operator!");
+                let info1 = match get_op_info(&t) {
+                    Some(info) => info,
+                    None => return Err(ParseError::new(&format!("unknown operator '{}'", s), Some(pos)))
+                };
 
-                while let Some(op2) = oper_stack.pop() {
+                while let Some((op2, pos2)) = oper_stack.pop() {
                     if let Token::Oper(_) = op2 {
                         if let Some(info2) = get_op_info(&op2) {
                             if info1.left_assoc && info1.precedence <= info2.precedence {
-                                output.insert (0, op2);
+                                output.insert (0, (op2, pos2));
                             } else if !info1.left_assoc && info1.precedence < info2.precedence {
-                                output.insert(0, op2);
+                                output.insert(0, (op2, pos2));
                             } else {
-                                oper_stack.push(op2);
+                                oper_stack.push((op2, pos2));
                                 break;
                             }
                         } else {
-                            panic!("Unknown operator!");
+                            return Err(ParseError::new("unknown operator", Some(pos2)));
                         }
                     } else {
-                        oper_stack.push(op2);
+                        oper_stack.push((op2, pos2));
                         break;
                     }
                 }
 
-                oper_stack.push(Token::Oper(s.clone()));
+                oper_stack.push((Token::Oper(s.clone()), pos));
             },
-            Token::OParen | Token::OBracket => oper_stack.push(t),
+            Token::OParen | Token::OBracket => oper_stack.push((t, pos)),
             Token::CParen | Token::CBracket => {
                 loop {
                     match oper_stack.pop() {
-                        Some(Token::OParen) | Some(Token::OBracket) => {
+                        Some((Token::OParen, _)) | Some((Token::OBracket, _)) => {
                             match oper_stack.pop() {
-                                Some(Token::Word(ref s)) => output.insert(0, Token::Word(s.clone())),
+                                Some((Token::Word(ref s), wpos)) => output.insert(0, (Token::Word(s.clone()), wpos)),
                                 Some(s) => oper_stack.push(s),
                                 None => {}
                             }
@@ -119,29 +410,171 @@ fn to_postfix(tokens: &mut Vec<Token>, output: &mut Vec<Token>) {
                             break;
                         },
                         Some(o) => output.insert(0, o),
-                        None => panic!("Mismatched parens or brackets!")
+                        None => return Err(ParseError::new("mismatched parens or brackets", Some(pos)))
                     }
                 }
             },
         }
     }
 
-    while let Some(t) = oper_stack.pop() {
+    while let Some((t, pos)) = oper_stack.pop() {
         match t {
             Token::OParen | Token::CParen | Token::OBracket | Token::CBracket =>
-                panic!("Mismatched parens or brackets!"),
-            _ => output.insert(0, t)
+                return Err(ParseError::new("mismatched parens or brackets", Some(pos))),
+            _ => output.insert(0, (t, pos))
         }
     }
+
+    Ok(())
 }
 
-pub fn parse(tokens: &mut Vec<Token>) -> Expression {
-    let mut output_queue: Vec<Token> = vec![];
+/// Consume an RPN token queue (as produced by `to_postfix`, where the front
+/// of the vec holds the *last* token) left-to-right into a value stack of
+/// `Expression`s. Numbers become leaves, operators pop their operands, a
+/// word in `calls` pops its recorded arity of arguments as a function call,
+/// and any other word is a bare variable reference with no arguments.
+/// Exactly one expression must remain once the queue is drained.
+fn build_expression(output: &mut Vec<(Token, usize)>, calls: &HashMap<usize, usize>) -> Result<Expression, ParseError> {
+    let mut stack: Vec<Box<Expression>> = vec![];
 
-    to_postfix(tokens, &mut output_queue);
+    while let Some((t, pos)) = output.pop() {
+        match t {
+            Token::Number(n) =>
+                stack.push(Box::new(Expression::Value(Rational::from_num(n)))),
+            Token::Oper(ref s) if build_unary_op(s).is_some() => {
+                let operand = match stack.pop() {
+                    Some(e) => e,
+                    None => return Err(ParseError::new("missing operand", Some(pos)))
+                };
+                stack.push(build_unary_op(s).unwrap().apply(operand));
+            },
+            Token::Oper(ref s) => {
+                let right = match stack.pop() {
+                    Some(e) => e,
+                    None => return Err(ParseError::new("missing right operand", Some(pos)))
+                };
+                let left = match stack.pop() {
+                    Some(e) => e,
+                    None => return Err(ParseError::new("missing left operand", Some(pos)))
+                };
+                let op = match build_binary_op(s) {
+                    Some(op) => op,
+                    None => return Err(ParseError::new(&format!("unknown operator '{}'", s), Some(pos)))
+                };
+                stack.push(op.apply(left, right));
+            },
+            Token::Word(name) => {
+                match calls.get(&pos) {
+                    Some(&arity) => {
+                        let mut args = Vec::with_capacity(arity);
+                        for _ in 0..arity {
+                            match stack.pop() {
+                                Some(e) => args.push(e),
+                                None => return Err(ParseError::new(&format!("missing argument for '{}'", name), Some(pos)))
+                            }
+                        }
+                        args.reverse();
+                        stack.push(Box::new(Expression::Call(name, args)));
+                    },
+                    None => stack.push(Box::new(Expression::Call(name, vec![])))
+                }
+            },
+            _ => return Err(ParseError::new(&format!("unexpected token {:?}", t), Some(pos)))
+        }
+    }
+
+    if stack.len() != 1 {
+        return Err(ParseError::new("malformed expression", None));
+    }
+
+    Ok(*stack.pop().unwrap())
+}
+
+/// `word = expr` is recognized as an assignment up front, before the
+/// generic shunting-yard pass: `=` isn't in `get_op_info` at all, so the
+/// rest of the expression is parsed independently and wrapped.
+pub fn parse(tokens: &mut Vec<(Token, usize)>) -> Result<Expression, ParseError> {
+    let assign_target = match (tokens.get(0), tokens.get(1)) {
+        (Some(&(Token::Word(ref name), _)), Some(&(Token::Oper(ref op), _))) if op.as_str() == "=" =>
+            Some(name.clone()),
+        _ => None
+    };
+
+    if let Some(name) = assign_target {
+        let mut rest = tokens.split_off(2);
+        let value = parse(&mut rest)?;
+        return Ok(Expression::Assign(name, Box::new(value)));
+    }
 
-    // Temporary return value
-    return Expression::Value(Rational::from_num(Num::Integer(0)));
+    let mut output_queue: Vec<(Token, usize)> = vec![];
+    let mut calls: HashMap<usize, usize> = HashMap::new();
+
+    to_postfix(tokens, &mut output_queue, &mut calls)?;
+
+    build_expression(&mut output_queue, &calls)
+}
+
+/// Runtime state threaded through evaluation: variables assigned so far,
+/// pre-populated with the constants `pi` and `e`. Builtin functions
+/// (`sin`, `cos`, ...) are pure and don't need any state, so they're
+/// dispatched by name in `evaluate` rather than stored here.
+pub struct Env {
+    vars: HashMap<String, Rational>
+}
+
+impl Env {
+    pub fn new() -> Env {
+        let mut vars = HashMap::new();
+        vars.insert("pi".to_string(), Rational::from_num(Num::Decimal(3, 141592653589793, 0)));
+        vars.insert("e".to_string(), Rational::from_num(Num::Decimal(2, 718281828459045, 0)));
+        Env { vars: vars }
+    }
+}
+
+/// Apply one of the unary built-in functions. They're computed via a lossy
+/// round-trip through `f64` (except `abs`, which is exact), since a
+/// transcendental result generally isn't representable as an exact
+/// `Rational`.
+fn call_builtin(name: &str, arg: Rational) -> Result<Rational, EvalError> {
+    match name {
+        "abs" => Ok(if arg.is_negative() { -arg } else { arg }),
+        "sqrt" => {
+            if arg.is_negative() {
+                return Err(EvalError { message: "sqrt of a negative number".to_string() });
+            }
+            Ok(Rational::from_f64_approx(arg.to_f64().sqrt()))
+        },
+        "sin" => Ok(Rational::from_f64_approx(arg.to_f64().sin())),
+        "cos" => Ok(Rational::from_f64_approx(arg.to_f64().cos())),
+        "tan" => Ok(Rational::from_f64_approx(arg.to_f64().tan())),
+        _ => Err(EvalError { message: format!("unknown function '{}'", name) })
+    }
+}
+
+/// Walk an `Expression` tree and compute its value, resolving variables
+/// against (and, for assignments, writing into) `env`.
+pub fn evaluate(expr: &Expression, env: &mut Env) -> Result<Rational, EvalError> {
+    match *expr {
+        Expression::Value(ref r) => Ok(r.clone()),
+        Expression::UnaryOp(ref op, ref e) => op.eval(evaluate(e, env)?),
+        Expression::BinaryOp(ref op, ref l, ref r) => op.eval(evaluate(l, env)?, evaluate(r, env)?),
+        Expression::Call(ref name, ref args) => {
+            if args.is_empty() {
+                env.vars.get(name).cloned()
+                    .ok_or_else(|| EvalError { message: format!("unknown variable '{}'", name) })
+            } else if args.len() == 1 {
+                let arg = evaluate(&args[0], env)?;
+                call_builtin(name, arg)
+            } else {
+                Err(EvalError { message: format!("'{}' takes exactly one argument", name) })
+            }
+        },
+        Expression::Assign(ref name, ref e) => {
+            let value = evaluate(e, env)?;
+            env.vars.insert(name.clone(), value.clone());
+            Ok(value)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -149,6 +582,7 @@ mod tests {
     use super::to_postfix;
     use lexer::Token;
     use lexer::Num;
+    use std::collections::HashMap;
 
     // Perhaps I shouldn't use other modules in test cases, but it makes them
     // very pretty
@@ -162,15 +596,18 @@ mod tests {
     }
 
     fn test_postfix(infix: &str, postfix: &str) {
-        let mut input = lex(quiet_from_str(infix));
+        let mut input = lex(quiet_from_str(infix)).unwrap();
         let mut output = vec![];
-        let expected = lex(quiet_from_str(postfix));
+        let expected: Vec<Token> = lex(quiet_from_str(postfix)).unwrap()
+            .into_iter().map(|(t, _)| t).collect();
 
-        to_postfix(&mut input, &mut output);
+        to_postfix(&mut input, &mut output, &mut HashMap::new()).unwrap();
 
         output.reverse ();
 
-        assert_eq!(output, expected);
+        let tokens: Vec<Token> = output.into_iter().map(|(t, _)| t).collect();
+
+        assert_eq!(tokens, expected);
     }
 
     #[test]
@@ -194,4 +631,113 @@ mod tests {
         test_postfix("1 + fun(2 + (3-4)) * 5", "1 2 3 4 - + fun 5 * +");
         test_postfix("1 / fun(2 * 3, 4 + 5)", "1 2 3 * 4 5 + fun /");
     }
+
+    #[test]
+    fn mismatched_parens_is_an_error() {
+        let mut input = lex(quiet_from_str("(1 + 2")).unwrap();
+        let mut output = vec![];
+
+        assert!(to_postfix(&mut input, &mut output, &mut HashMap::new()).is_err());
+    }
+
+    fn eval_str(s: &str) -> String {
+        let mut tokens = lex(quiet_from_str(s)).unwrap();
+        let expr = super::parse(&mut tokens).unwrap();
+        super::evaluate(&expr, &mut super::Env::new()).unwrap().to_string()
+    }
+
+    #[test]
+    fn unary_minus() {
+        // Unary minus binds tighter than */ but looser than ^, so this is
+        // -(2^2), not (-2)^2.
+        assert_eq!("-4", eval_str("-2^2"));
+        assert_eq!("-1", eval_str("3 + -4"));
+        assert_eq!("-6", eval_str("2 * -3"));
+        assert_eq!("3", eval_str("- -3"));
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        let mut tokens = lex(quiet_from_str("1 / 0")).unwrap();
+        let expr = super::parse(&mut tokens).unwrap();
+        assert!(super::evaluate(&expr, &mut super::Env::new()).is_err());
+    }
+
+    #[test]
+    fn variables_and_constants() {
+        let mut env = super::Env::new();
+
+        let mut assign = lex(quiet_from_str("x = 2 + 3")).unwrap();
+        let assign_expr = super::parse(&mut assign).unwrap();
+        assert_eq!("5", super::evaluate(&assign_expr, &mut env).unwrap().to_string());
+
+        let mut usage = lex(quiet_from_str("x * 2")).unwrap();
+        let usage_expr = super::parse(&mut usage).unwrap();
+        assert_eq!("10", super::evaluate(&usage_expr, &mut env).unwrap().to_string());
+
+        let mut unknown = lex(quiet_from_str("y")).unwrap();
+        let unknown_expr = super::parse(&mut unknown).unwrap();
+        assert!(super::evaluate(&unknown_expr, &mut env).is_err());
+    }
+
+    #[test]
+    fn builtins() {
+        assert_eq!("3", eval_str("abs(-3)"));
+        assert_eq!("4", eval_str("sqrt(16)"));
+    }
+
+    #[test]
+    fn sqrt_of_a_negative_is_an_error() {
+        let mut tokens = lex(quiet_from_str("sqrt(-1)")).unwrap();
+        let expr = super::parse(&mut tokens).unwrap();
+        assert!(super::evaluate(&expr, &mut super::Env::new()).is_err());
+    }
+
+    #[test]
+    fn pow_with_an_unsupported_exponent_is_an_error() {
+        // Rational::pow only supports non-negative integer exponents; a
+        // fractional or negative one must come back as an EvalError
+        // instead of panicking and taking the whole process down.
+        let mut negative = lex(quiet_from_str("2^-1")).unwrap();
+        let negative_expr = super::parse(&mut negative).unwrap();
+        assert!(super::evaluate(&negative_expr, &mut super::Env::new()).is_err());
+
+        let mut fractional = lex(quiet_from_str("2^0.5")).unwrap();
+        let fractional_expr = super::parse(&mut fractional).unwrap();
+        assert!(super::evaluate(&fractional_expr, &mut super::Env::new()).is_err());
+    }
+
+    #[test]
+    fn multi_arg_call_parses_with_its_full_arity() {
+        // No built-in takes two arguments, so this must fail in `evaluate`
+        // (arity mismatch) rather than in `parse` (malformed expression) -
+        // i.e. both of its arguments must actually make it into the `Call`.
+        let mut tokens = lex(quiet_from_str("fun(1, 2)")).unwrap();
+        let expr = super::parse(&mut tokens).unwrap();
+
+        match expr {
+            super::Expression::Call(ref name, ref args) => {
+                assert_eq!("fun", name);
+                assert_eq!(2, args.len());
+            },
+            _ => panic!("expected a Call expression")
+        }
+
+        assert!(super::evaluate(&expr, &mut super::Env::new()).is_err());
+    }
+
+    #[test]
+    fn unary_minus_with_no_space_after_the_binary_operator() {
+        // lex_oper merges "^-", "*-", "+-" into one compound token when
+        // there's no space to keep them apart; all three must still parse
+        // as a binary operator followed by a unary sign rather than fail
+        // with e.g. "unknown operator '*-'".
+        assert_eq!("-6", eval_str("3*-2"));
+        assert_eq!("2", eval_str("5+-3"));
+
+        // `Rational::pow` doesn't support negative exponents yet, so `2^-1`
+        // can't go through `eval_str`, but it must still parse.
+        let mut tokens = lex(quiet_from_str("2^-1")).unwrap();
+        assert!(super::parse(&mut tokens).is_ok());
+    }
 }
@@ -2,27 +2,205 @@ use lexer::Num;
 
 extern crate num;
 
-#[derive(Debug)]
+use num::{BigInt, Integer, Signed, ToPrimitive, Zero};
+
+use std::error::Error;
+use std::fmt;
+use std::ops;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Rational {
-    num: i64,
-    den: i64,
+    pub(crate) num: BigInt,
+    pub(crate) den: BigInt,
+}
+
+/// Division by a zero `Rational`. Returned rather than panicking so a
+/// REPL can report it and keep going.
+#[derive(Debug)]
+pub struct DivByZeroError;
+
+impl fmt::Display for DivByZeroError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "division by zero")
+    }
+}
+
+impl Error for DivByZeroError {}
+
+/// Raising to a negative or non-integer exponent, neither of which `pow`
+/// supports yet. Returned rather than panicking so a REPL can report it
+/// and keep going.
+#[derive(Debug)]
+pub struct UnsupportedExponentError;
+
+impl fmt::Display for UnsupportedExponentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "only non-negative integer exponents are supported")
+    }
+}
+
+impl Error for UnsupportedExponentError {}
+
+/// Number of base-10 digits in `n`. `0` has no digits of its own, but that's
+/// fine since it never contributes to the value it's scaled by anyway.
+fn digit_count(n: u64) -> i16 {
+    let mut n = n;
+    let mut count = 0;
+
+    while n > 0 {
+        n /= 10;
+        count += 1;
+    }
+
+    count
 }
 
 impl Rational {
     pub fn from_num (number: Num) -> Rational {
         match number {
-            Num::Integer (x) => Rational { num: x, den: 1 },
+            Num::Integer (x) => Rational { num: BigInt::from(x), den: BigInt::from(1) },
             Num::Decimal (whole, dec, exponent) => {
-                if exponent >= 0 {
+                if exponent > 0 {
                     panic!("Positive exponent");
                 }
 
-                let denominator = num::pow (10, (-exponent) as usize);
-                let numerator = (whole * denominator) + (dec as i64);
+                // `exponent` only counts zeroes stripped off the front of the
+                // decimal part (e.g. ".001" is `Decimal(0, 1, -2)`), so the
+                // denominator also needs to cover `dec`'s own digits (the
+                // `141592653589793` of `3.141592653589793` is as much a part
+                // of the scale as the sign of `exponent` is).
+                let scale = digit_count(dec) - exponent;
+                let denominator = num::pow (BigInt::from(10), scale as usize);
+                let numerator = (BigInt::from(whole) * &denominator) + BigInt::from(dec);
 
-                Rational { num: numerator, den: denominator }
+                Rational { num: numerator, den: denominator }.reduced()
             }
         }
     }
+
+    /// Divide numerator and denominator by their GCD and make sure the
+    /// denominator is positive, so every `Rational` is always in lowest
+    /// terms with a canonical sign.
+    fn reduced(self) -> Rational {
+        if self.num.is_zero() {
+            return Rational { num: BigInt::from(0), den: BigInt::from(1) };
+        }
+
+        let gcd = self.num.gcd(&self.den);
+        let mut num = self.num / &gcd;
+        let mut den = self.den / &gcd;
+
+        if den.is_negative() {
+            num = -num;
+            den = -den;
+        }
+
+        Rational { num: num, den: den }
+    }
+
+    /// Exponentiation by repeated multiplication. Only non-negative integer
+    /// exponents are supported for now; anything else is an error rather
+    /// than a panic.
+    pub fn pow (&self, exponent: &Rational) -> Result<Rational, UnsupportedExponentError> {
+        if exponent.den != BigInt::from(1) || exponent.num.is_negative() {
+            return Err(UnsupportedExponentError);
+        }
+
+        let mut result = Rational { num: BigInt::from(1), den: BigInt::from(1) };
+        let mut i = BigInt::from(0);
+        while i < exponent.num {
+            result = result * self.clone();
+            i = i + BigInt::from(1);
+        }
+
+        Ok(result)
+    }
+
+    /// Lossy conversion to a 64-bit float, for feeding into functions (like
+    /// the trigonometric built-ins) that have no exact rational result.
+    pub fn to_f64(&self) -> f64 {
+        self.num.to_f64().unwrap_or(0.0) / self.den.to_f64().unwrap_or(1.0)
+    }
+
+    /// Whether this value is less than zero. The denominator is always
+    /// kept positive, so the sign lives entirely in the numerator.
+    pub fn is_negative(&self) -> bool {
+        self.num.is_negative()
+    }
+
+    /// Approximate a float as a `Rational` by rounding it to a fixed number
+    /// of decimal places. Used to bring the result of a transcendental
+    /// function (which generally isn't rational at all) back into
+    /// `Rational`'s exact-fraction representation.
+    pub fn from_f64_approx(x: f64) -> Rational {
+        let scale = 1_000_000_000i64;
+        let num = BigInt::from((x * scale as f64).round() as i64);
+        Rational { num: num, den: BigInt::from(scale) }.reduced()
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.den == BigInt::from(1) {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+/// Cross-multiply addition, reduced to lowest terms: a/b + c/d = (ad+bc)/bd.
+impl ops::Add for Rational {
+    type Output = Rational;
+
+    fn add(self, other: Rational) -> Rational {
+        let num = &self.num * &other.den + &other.num * &self.den;
+        let den = self.den * other.den;
+        Rational { num: num, den: den }.reduced()
+    }
+}
+
+/// Cross-multiply subtraction, reduced to lowest terms: a/b - c/d = (ad-bc)/bd.
+impl ops::Sub for Rational {
+    type Output = Rational;
+
+    fn sub(self, other: Rational) -> Rational {
+        let num = &self.num * &other.den - &other.num * &self.den;
+        let den = self.den * other.den;
+        Rational { num: num, den: den }.reduced()
+    }
+}
+
+/// Straightforward multiplication, reduced to lowest terms.
+impl ops::Mul for Rational {
+    type Output = Rational;
+
+    fn mul(self, other: Rational) -> Rational {
+        Rational { num: self.num * other.num, den: self.den * other.den }.reduced()
+    }
+}
+
+/// Division by cross-multiplying the reciprocal, reduced to lowest terms.
+/// Dividing by zero is an error rather than a panic.
+impl ops::Div for Rational {
+    type Output = Result<Rational, DivByZeroError>;
+
+    fn div(self, other: Rational) -> Result<Rational, DivByZeroError> {
+        if other.num.is_zero() {
+            return Err(DivByZeroError);
+        }
+
+        let num = self.num * &other.den;
+        let den = self.den * &other.num;
+        Ok(Rational { num: num, den: den }.reduced())
+    }
 }
 
+/// Flip the sign.
+impl ops::Neg for Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational { num: -self.num, den: self.den }
+    }
+}
@@ -16,15 +16,23 @@ pub fn read() -> String {
 	}
 }
 
-pub fn handle(result: String) -> () {
+pub fn handle(result: String, env: &mut parser::Env) -> () {
 	// Strip '\n'
 	let mut input = result;
 	input.pop ();
 
-	let mut tokens = lexer::lex (input);
+	let mut tokens = match lexer::lex (input) {
+		Ok(tokens) => tokens,
+		Err(e) => { println!("{}", e); return; }
+	};
 
-	println!("Lexed: {:?}", tokens);
-	let parsed = parser::parse (&mut tokens);
+	let parsed = match parser::parse (&mut tokens) {
+		Ok(parsed) => parsed,
+		Err(e) => { println!("{}", e); return; }
+	};
 
-	//println!("Parsed: {:?}", parsed);
+	match parser::evaluate (&parsed, env) {
+		Ok(value) => println!("{}", value),
+		Err(e) => println!("{}", e),
+	}
 }
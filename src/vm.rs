@@ -0,0 +1,169 @@
+use parser::Expression;
+use rational::Rational;
+
+use std::error::Error;
+use std::fmt;
+
+/// A single instruction for the stack machine that `compile` lowers an
+/// `Expression` into.
+#[derive(Debug, Clone)]
+pub enum Instr {
+    Push(Rational),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg,
+    Call(String, usize),
+}
+
+/// An error encountered while lowering an `Expression` to bytecode.
+#[derive(Debug)]
+pub struct CompileError {
+    pub message: String
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for CompileError {}
+
+/// An error encountered while executing a compiled instruction stream.
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub message: String
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for RuntimeError {}
+
+/// Lower an `Expression` into a flat instruction stream. This is a
+/// post-order walk: children are compiled first, then the operator's
+/// instruction is emitted, which mirrors the RPN order `to_postfix`
+/// already produces.
+pub fn compile(expr: &Expression) -> Result<Vec<Instr>, CompileError> {
+    let mut instrs = vec![];
+    compile_into(expr, &mut instrs)?;
+    Ok(instrs)
+}
+
+fn compile_into(expr: &Expression, instrs: &mut Vec<Instr>) -> Result<(), CompileError> {
+    match *expr {
+        Expression::Value(ref r) => instrs.push(Instr::Push(r.clone())),
+        Expression::UnaryOp(ref op, ref e) => {
+            compile_into(e, instrs)?;
+            op.compile(instrs)?;
+        },
+        Expression::BinaryOp(ref op, ref l, ref r) => {
+            compile_into(l, instrs)?;
+            compile_into(r, instrs)?;
+            op.compile(instrs)?;
+        },
+        Expression::Call(ref name, ref args) => {
+            for arg in args {
+                compile_into(arg, instrs)?;
+            }
+            instrs.push(Instr::Call(name.clone(), args.len()));
+        },
+        Expression::Assign(ref name, _) =>
+            return Err(CompileError { message: format!("assigning to '{}' is not supported by the bytecode compiler", name) })
+    }
+
+    Ok(())
+}
+
+/// Execute a compiled instruction stream against an operand stack of
+/// `Rational`s. Each binary instruction pops the right operand then the
+/// left; popping an empty stack (underflow) and finishing with anything
+/// other than exactly one value left over (overflow) are both reported as
+/// runtime errors rather than panics.
+pub fn execute(instrs: &[Instr]) -> Result<Rational, RuntimeError> {
+    let mut stack: Vec<Rational> = vec![];
+
+    let underflow = || RuntimeError { message: "operand stack underflow".to_string() };
+
+    for instr in instrs {
+        match *instr {
+            Instr::Push(ref r) => stack.push(r.clone()),
+            Instr::Neg => {
+                let val = stack.pop().ok_or_else(underflow)?;
+                stack.push(-val);
+            },
+            Instr::Add | Instr::Sub | Instr::Mul | Instr::Div => {
+                let right = stack.pop().ok_or_else(underflow)?;
+                let left = stack.pop().ok_or_else(underflow)?;
+
+                let result = match *instr {
+                    Instr::Add => left + right,
+                    Instr::Sub => left - right,
+                    Instr::Mul => left * right,
+                    Instr::Div => (left / right).map_err(|e| RuntimeError { message: e.to_string() })?,
+                    _ => unreachable!()
+                };
+
+                stack.push(result);
+            },
+            Instr::Call(ref name, _) =>
+                return Err(RuntimeError { message: format!("function '{}' is not yet supported", name) })
+        }
+    }
+
+    if stack.len() != 1 {
+        return Err(RuntimeError { message: "operand stack overflow: expression left more than one value".to_string() });
+    }
+
+    Ok(stack.pop().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, execute};
+    use lexer::lex;
+    use parser;
+
+    // String::from_str gives a lot of warnings, this is just a workaround
+    fn quiet_from_str(s: &str) -> String {
+        let mut string = String::new();
+        string.push_str(s);
+        return string;
+    }
+
+    fn run(s: &str) -> String {
+        let mut tokens = lex(quiet_from_str(s)).unwrap();
+        let expr = parser::parse(&mut tokens).unwrap();
+        let instrs = compile(&expr).unwrap();
+        execute(&instrs).unwrap().to_string()
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!("14", run("2 + 3 * 4"));
+        assert_eq!("-6", run("2 * -3"));
+        assert_eq!("1/2", run("1 / 2"));
+    }
+
+    #[test]
+    fn division_by_zero_is_a_runtime_error() {
+        let mut tokens = lex(quiet_from_str("1 / 0")).unwrap();
+        let expr = parser::parse(&mut tokens).unwrap();
+        let instrs = compile(&expr).unwrap();
+
+        assert!(execute(&instrs).is_err());
+    }
+
+    #[test]
+    fn exponentiation_is_not_yet_compilable() {
+        let mut tokens = lex(quiet_from_str("2^3")).unwrap();
+        let expr = parser::parse(&mut tokens).unwrap();
+
+        assert!(compile(&expr).is_err());
+    }
+}